@@ -0,0 +1,329 @@
+//! Reads the declarative opcode tables and emits the generated `AsmLine`
+//! enum/parser/emitter code to `OUT_DIR`, so the two transpiler binaries
+//! only have to `include!` the result. Adding a new opcode or
+//! argument-pattern combination means editing the relevant `.in` file once
+//! instead of the enum, the parser match, and the `Display` match.
+//!
+//! `instructions.in` feeds the monolithic `src/bin/transpiler.rs` binary.
+//! `instructions-modular.in` feeds the modular `src/bin/transpiler/` binary,
+//! whose `AsmLine`/`Arg` types live in `asm_line.rs`/`arg.rs` instead.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+struct OpcodeBlock {
+    mnemonics: Vec<String>,
+    variant: String,
+    arg_count: usize,
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    patterns: Vec<(String, SlotGuard)>,
+    same: bool, // whole-pattern `same:` equality guard
+    template: Vec<String>,
+}
+
+/// A per-slot prefix on a rule's pattern, parsed off the `Arg` kind.
+#[derive(PartialEq)]
+enum SlotGuard {
+    /// No prefix: the slot's Rust binding is used as matched.
+    None,
+    /// `neg:` -- only matches a `Literal` below zero, and rebinds it to its
+    /// negation before rendering (e.g. turning `addb $-5, %eax` into a
+    /// `SBW`/`SBC` of the positive magnitude).
+    Neg,
+    /// `bias:` -- always matches; additionally binds `{slot}_signed` to
+    /// the `Literal`'s value with its sign bit (bit 15 of the 16-bit
+    /// value) flipped, alongside the untouched `{slot}`. XOR-ing that one
+    /// bit turns a two's-complement ordering into an equivalent unsigned
+    /// one, so templates can run the existing unsigned compare-and-branch
+    /// sequence against `{slot}_signed`'s high byte to get a signed result.
+    Bias,
+}
+
+fn default_var(kind: &str) -> &'static str {
+    match kind {
+        "Literal" => "literal",
+        "AbsoluteAddress" => "addr",
+        "VirtualRegister" => "reg",
+        "Label" => "label",
+        _ => "_",
+    }
+}
+
+/// Assigns a Rust binding name to every slot in a rule's pattern list.
+/// A kind used only once keeps its usual name (`literal`, `reg`, ...); a
+/// kind repeated across slots (e.g. `VirtualRegister,VirtualRegister`)
+/// instead gets `op1`/`op2`, and templates must use `{op1}`/`{op2}` to
+/// substitute it. `Accumulator` and `SumAddress` are special-cased
+/// elsewhere and never take part in this naming.
+fn rule_slot_vars(patterns: &[(String, SlotGuard)]) -> Vec<String> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for (kind, _) in patterns {
+        if kind != "Accumulator" && kind != "SumAddress" {
+            *counts.entry(kind.as_str()).or_insert(0) += 1;
+        }
+    }
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    patterns
+        .iter()
+        .map(|(kind, _)| {
+            if kind == "Accumulator" || kind == "SumAddress" {
+                String::new()
+            } else if counts[kind.as_str()] > 1 {
+                let idx = seen.entry(kind.as_str()).or_insert(0);
+                *idx += 1;
+                format!("op{}", idx)
+            } else {
+                default_var(kind).to_string()
+            }
+        })
+        .collect()
+}
+
+fn parse_instructions(src: &str) -> Vec<OpcodeBlock> {
+    let mut blocks = vec![];
+    let mut current: Option<OpcodeBlock> = None;
+
+    for raw_line in src.lines() {
+        let line = raw_line.trim_end();
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        if !line.starts_with(' ') && !line.starts_with('\t') {
+            if let Some(block) = current.take() {
+                blocks.push(block);
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            assert_eq!(parts[0], "opcode", "malformed instructions line: {}", line);
+            current = Some(OpcodeBlock {
+                mnemonics: parts[1].split(',').map(str::to_string).collect(),
+                variant: parts[2].to_string(),
+                arg_count: parts[3].parse().unwrap(),
+                rules: vec![],
+            });
+            continue;
+        }
+
+        let block = current.as_mut().expect("rule line before any opcode block");
+        let (pattern_str, template_str) = line
+            .trim()
+            .split_once("=>")
+            .expect("rule line missing '=>'");
+
+        let pattern_str = pattern_str.trim();
+        let (same, pattern_str) = match pattern_str.strip_prefix("same:") {
+            Some(rest) => (true, rest),
+            None => (false, pattern_str),
+        };
+
+        let patterns = pattern_str
+            .split(',')
+            .map(|p| {
+                let p = p.trim();
+                if let Some(rest) = p.strip_prefix("neg:") {
+                    (rest.to_string(), SlotGuard::Neg)
+                } else if let Some(rest) = p.strip_prefix("bias:") {
+                    (rest.to_string(), SlotGuard::Bias)
+                } else {
+                    (p.to_string(), SlotGuard::None)
+                }
+            })
+            .collect();
+
+        let template = template_str
+            .trim()
+            .split('/')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        block.rules.push(Rule { patterns, same, template });
+    }
+    if let Some(block) = current.take() {
+        blocks.push(block);
+    }
+    blocks
+}
+
+fn arg_pattern(kind: &str, var: &str) -> String {
+    match kind {
+        "Accumulator" => "Arg::Accumulator".to_string(),
+        "SumAddress" => "Arg::SumAddress(op1, op2)".to_string(),
+        _ => format!("Arg::{}({})", kind, var),
+    }
+}
+
+/// Joins template lines into the text of a single `writeln!` argument.
+/// Every line is tab-indented except one starting with `@` (an inline
+/// anonymous label), which is emitted bare. Holes like `{reg}`/`{op1}`
+/// are left untouched: `rule_slot_vars` names each pattern's Rust
+/// binding to match its hole exactly, so the template is already a
+/// valid captured-identifier format string once tab-joined.
+fn join_template(lines: &[String]) -> String {
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            out.push_str("\\n");
+        }
+        if !line.starts_with('@') {
+            out.push_str("\\t");
+        }
+        out.push_str(line);
+    }
+    out
+}
+
+fn rule_arm(variant: &str, rule: &Rule) -> String {
+    let vars = rule_slot_vars(&rule.patterns);
+    let pat_parts: Vec<String> = rule
+        .patterns
+        .iter()
+        .zip(&vars)
+        .map(|((kind, _), var)| arg_pattern(kind, var))
+        .collect();
+
+    let neg_slot = rule.patterns.iter().position(|(_, g)| *g == SlotGuard::Neg);
+    let bias_slot = rule.patterns.iter().position(|(_, g)| *g == SlotGuard::Bias);
+    let guard = if let Some(slot) = neg_slot {
+        format!(" if {} < &0i32", vars[slot])
+    } else if rule.same {
+        format!(" if {} == {}", vars[0], vars[1])
+    } else {
+        String::new()
+    };
+
+    let body = if rule.template.is_empty() {
+        "Ok(())".to_string()
+    } else {
+        let rendered = join_template(&rule.template);
+        match (neg_slot, bias_slot) {
+            (Some(slot), _) => format!(
+                "{{ let {0} = -*{0}; writeln!(f, \"{1}\") }}",
+                vars[slot], rendered
+            ),
+            (None, Some(slot)) => format!(
+                "{{ let {0}_signed = *{0} ^ 0x8000i32; writeln!(f, \"{1}\") }}",
+                vars[slot], rendered
+            ),
+            (None, None) => format!("writeln!(f, \"{}\")", rendered),
+        }
+    };
+
+    format!(
+        "            Self::{}({}){} => {},\n",
+        variant,
+        pat_parts.join(", "),
+        guard,
+        body
+    )
+}
+
+fn fallback_arm(variant: &str) -> String {
+    format!(
+        "            Self::{}(l, ..) => writeln!(f, \"Unable to generate code for opcode '{}' with arguments: {{:?}}\", l),\n",
+        variant,
+        variant.to_uppercase()
+    )
+}
+
+fn generate_enum(blocks: &[OpcodeBlock]) -> String {
+    let mut out = String::new();
+    out.push_str("#[derive(Debug)]\npub(in crate) enum AsmLine {\n    Label(String),\n");
+    for block in blocks {
+        let args = (0..block.arg_count).map(|_| "Arg").collect::<Vec<_>>().join(", ");
+        out.push_str(&format!("    {}({}),\n", block.variant, args));
+    }
+    out.push_str("}\n\n");
+    out
+}
+
+fn generate_display(blocks: &[OpcodeBlock]) -> String {
+    let mut out = String::new();
+    out.push_str("impl fmt::Display for AsmLine {\n");
+    out.push_str("    #[allow(clippy::many_single_char_names)]\n");
+    out.push_str("    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {\n        match self {\n");
+    out.push_str("            Self::Label(l) => writeln!(f, \"{}\", l),\n");
+    for block in blocks {
+        for rule in &block.rules {
+            out.push_str(&rule_arm(&block.variant, rule));
+        }
+        out.push_str(&fallback_arm(&block.variant));
+    }
+    out.push_str("        }\n    }\n}\n");
+    out
+}
+
+/// Generates the `AsmLine::parse` dispatch shared by both binaries: a
+/// span-carrying `tokenize`-based match on the opcode, handed off to the
+/// hand-written `opcode_with_*_arg(s)!` macros and `AsmLine::args` helper.
+fn generate_parse(blocks: &[OpcodeBlock], in_path: &str) -> String {
+    let mut out = String::new();
+    out.push_str("impl AsmLine {\n");
+    out.push_str("    /// Parses a single source line, attaching `line_no`/`raw` to any\n");
+    out.push_str("    /// error so it can be reported with a caret under the bad token.\n");
+    out.push_str("    pub(in crate) fn parse(line_no: usize, raw: &str) -> Result<Self, AsmLineError> {\n");
+    out.push_str("        if let Some(stripped) = raw.strip_prefix('.') {\n            return Ok(Self::Label(stripped.to_string()));\n        }\n\n");
+    out.push_str("        let parts = tokenize(raw);\n");
+    out.push_str("        let mut iter = parts.iter();\n");
+    out.push_str("        if let Some((ostart, oend, opcode)) = iter.next() {\n            match opcode.as_str() {\n");
+    for block in blocks {
+        let mnemonics = block
+            .mnemonics
+            .iter()
+            .map(|m| format!("\"{}\"", m))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        let macro_name = match block.arg_count {
+            1 => "opcode_with_1_arg",
+            2 => "opcode_with_2_args",
+            n => panic!("unsupported arg_count {} in {}", n, in_path),
+        };
+        out.push_str(&format!(
+            "                {} => {}!(iter.as_slice(), Self::{}, line_no, raw),\n",
+            mnemonics, macro_name, block.variant
+        ));
+    }
+    out.push_str("                _ => return Err(AsmLineError::new(line_no, raw, (*ostart, *oend), AsmLineErrorKind::UnknownOpcode).with_detail(opcode.clone())),\n");
+    out.push_str("            }\n        }\n\n        Err(AsmLineError::new(line_no, raw, (0, raw.len()), AsmLineErrorKind::EmptyLine))\n    }\n}\n\n");
+    out
+}
+
+/// Generates the monolithic binary's `instrs.rs`: the `AsmLine` enum, an
+/// `AsmLine::parse` that attaches span diagnostics, and `Display`.
+fn generate_monolithic(blocks: &[OpcodeBlock]) -> String {
+    let mut out = generate_enum(blocks);
+    out.push_str(&generate_parse(blocks, "instructions.in"));
+    out.push_str(&generate_display(blocks));
+    out
+}
+
+/// Generates the modular binary's `instrs.rs`: the `AsmLine` enum, an
+/// `AsmLine::parse` that attaches span diagnostics, and `Display`.
+fn generate_modular(blocks: &[OpcodeBlock]) -> String {
+    let mut out = generate_enum(blocks);
+    out.push_str(&generate_parse(blocks, "instructions-modular.in"));
+    out.push_str(&generate_display(blocks));
+    out
+}
+
+fn write_generated(in_path: &str, out_name: &str, generate: impl Fn(&[OpcodeBlock]) -> String) {
+    println!("cargo:rerun-if-changed={}", in_path);
+
+    let src = fs::read_to_string(in_path).unwrap_or_else(|e| panic!("failed to read {}: {}", in_path, e));
+    let blocks = parse_instructions(&src);
+    let generated = generate(&blocks);
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let dest = Path::new(&out_dir).join(out_name);
+    fs::write(dest, generated).unwrap_or_else(|e| panic!("failed to write {}: {}", out_name, e));
+}
+
+fn main() {
+    write_generated("instructions.in", "instrs.rs", generate_monolithic);
+    write_generated("instructions-modular.in", "instrs_modular.rs", generate_modular);
+}
@@ -0,0 +1,114 @@
+// Shared linear-scan zero-page allocation core, `include!`d by both the
+// monolithic (`transpiler.rs`) and modular (`transpiler/regalloc.rs`)
+// binaries. The two don't share a lib crate, so this is the cheapest way
+// to keep the algorithm itself -- and its tie-break rules -- from
+// drifting into two copies; each binary still supplies its own
+// `touched_registers` since that has to pattern-match its own `AsmLine`.
+
+/// Base of the zero-page scratch area: `TMPW` at `+0`, `LAST_CMP` at `+2`,
+/// `SLAST_CMP` at `+3`, then the virtual-register pool.
+pub(in crate) const ZERO_PAGE_BASE: usize = 0x80;
+pub(in crate) const VIRTUAL_REGISTERS_BASE: usize = ZERO_PAGE_BASE + 4;
+/// Number of zero-page word slots available for virtual registers. Kept
+/// small on purpose: it's the scarce resource the allocator exists to share.
+const ZERO_PAGE_POOL_WORDS: usize = 8;
+/// Scratch area used once the zero-page pool is exhausted. Spilling here is
+/// free in this backend: a `VREG_x` is already a memory location rather
+/// than a real register, so an address outside the zero page just costs
+/// slightly slower absolute addressing, not extra load/store code.
+const SPILL_BASE: usize = 0x300;
+
+#[derive(Debug)]
+pub(in crate) struct LiveRange {
+    pub(in crate) start: usize,
+    pub(in crate) end: usize,
+}
+
+/// Builds per-register live ranges from the linear instruction stream, then
+/// does a linear-scan allocation: walk registers in definition order, free
+/// any zero-page slot whose owner has already died, and hand the lowest
+/// free slot to the newly-live register. Once the pool is exhausted, spill
+/// whichever active register dies last -- the newly-live one if nothing
+/// active outlives it, otherwise the active register itself, handing its
+/// freed slot to the newly-live one.
+pub(in crate) struct RegisterAllocator {
+    ranges: HashMap<char, LiveRange>,
+}
+
+impl RegisterAllocator {
+    /// Builds the allocator from `program`'s per-instruction touched
+    /// registers, via a per-binary `touched_registers` callback since the
+    /// instruction type itself (`AsmLine`) isn't shared between binaries.
+    pub(in crate) fn build<T>(program: &[T], touched_registers: impl Fn(&T) -> Vec<char>) -> Self {
+        let mut ranges: HashMap<char, LiveRange> = HashMap::new();
+        for (pc, line) in program.iter().enumerate() {
+            for reg in touched_registers(line) {
+                ranges
+                    .entry(reg)
+                    .and_modify(|lr| lr.end = pc)
+                    .or_insert(LiveRange { start: pc, end: pc });
+            }
+        }
+        RegisterAllocator { ranges }
+    }
+
+    pub(in crate) fn allocate(&self) -> HashMap<char, usize> {
+        let mut by_def_order: Vec<(char, &LiveRange)> =
+            self.ranges.iter().map(|(r, lr)| (*r, lr)).collect();
+        // `self.ranges` is a `HashMap`, so two registers first touched by
+        // the same instruction (e.g. both operands of `movl %eax, %ecx`)
+        // would otherwise tie on `lr.start` and fall back to the map's
+        // per-process random iteration order; break the tie on the
+        // register itself so allocation is deterministic.
+        by_def_order.sort_by_key(|(r, lr)| (lr.start, *r));
+
+        let mut assignment = HashMap::new();
+        let mut free_slots: Vec<usize> = (0..ZERO_PAGE_POOL_WORDS).rev().collect();
+        let mut active: Vec<(char, usize)> = vec![];
+        let mut spilled = 0usize;
+
+        for (reg, range) in &by_def_order {
+            active.retain(|(owner, slot)| {
+                let still_live = self.ranges[owner].end >= range.start;
+                if !still_live {
+                    free_slots.push(*slot);
+                }
+                still_live
+            });
+            // Keep the stack in descending order so `pop` yields the
+            // lowest free slot, not just the most recently freed one.
+            free_slots.sort_unstable_by(|a, b| b.cmp(a));
+
+            if let Some(slot) = free_slots.pop() {
+                active.push((*reg, slot));
+                assignment.insert(*reg, VIRTUAL_REGISTERS_BASE + (slot << 1));
+                continue;
+            }
+
+            // Pool exhausted: evict whichever active register has the
+            // furthest-away next use -- i.e. the longest remaining live
+            // range -- rather than always spilling the one that just
+            // became live.
+            let furthest = active
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, (owner, _))| self.ranges[owner].end)
+                .map(|(i, &(owner, slot))| (i, owner, self.ranges[&owner].end, slot));
+
+            match furthest {
+                Some((i, evicted, evicted_end, slot)) if evicted_end > range.end => {
+                    assignment.insert(evicted, SPILL_BASE + (spilled << 1));
+                    spilled += 1;
+                    active[i] = (*reg, slot);
+                    assignment.insert(*reg, VIRTUAL_REGISTERS_BASE + (slot << 1));
+                }
+                _ => {
+                    assignment.insert(*reg, SPILL_BASE + (spilled << 1));
+                    spilled += 1;
+                }
+            }
+        }
+
+        assignment
+    }
+}
@@ -0,0 +1,105 @@
+// Shared instruction-stream parsing, `include!`d by both the monolithic
+// (`transpiler.rs`'s `mod sim`) and modular (`transpiler/sim.rs`) binaries.
+// Turning the plain-text 6502 source into a `Program` is identical between
+// them; only the `Cpu` that executes it differs, since the two binaries
+// disagree on how memory is addressed (a plain `Vec<u8>` here vs. the
+// `Addressable`/`Bus` abstraction the modular binary needs for MMIO).
+
+#[derive(Debug, Clone)]
+pub(in crate) struct SourceLine {
+    pub(in crate) label: Option<String>,
+    pub(in crate) mnemonic: Option<String>,
+    pub(in crate) operands: Vec<String>,
+}
+
+pub(in crate) fn parse_line(raw: &str) -> Option<SourceLine> {
+    let line = raw.split(';').next().unwrap().trim();
+    if line.is_empty() {
+        return None;
+    }
+
+    let indented = raw.starts_with(' ') || raw.starts_with('\t');
+    let mut tokens = line.split_whitespace();
+    let first = tokens.next()?.to_string();
+
+    if indented {
+        return Some(SourceLine {
+            label: None,
+            mnemonic: Some(first.to_uppercase()),
+            operands: tokens.map(str::to_string).collect(),
+        });
+    }
+
+    let rest: Vec<String> = tokens.map(str::to_string).collect();
+    if rest.is_empty() {
+        return Some(SourceLine {
+            label: Some(first),
+            mnemonic: None,
+            operands: vec![],
+        });
+    }
+    Some(SourceLine {
+        label: Some(first),
+        mnemonic: Some(rest[0].to_uppercase()),
+        operands: rest[1..].to_vec(),
+    })
+}
+
+#[derive(Debug, Default)]
+pub(in crate) struct Program {
+    pub(in crate) instructions: Vec<SourceLine>,
+    pub(in crate) labels: HashMap<String, usize>,
+    pub(in crate) symbols: HashMap<String, u16>,
+    /// Every position an anonymous `@` label was seen, in program order, so
+    /// `@+` can resolve to "the next one after here" instead of colliding
+    /// in `labels` like a named label would.
+    pub(in crate) anon_labels: Vec<usize>,
+}
+
+/// One pass over the generated source collects `equ` bindings, label
+/// positions and instructions. Operands are only resolved when `Cpu::step`
+/// executes them, so forward references (a loop branching to a label
+/// defined further down) resolve correctly.
+pub(in crate) fn assemble(source: &str) -> Program {
+    let mut program = Program::default();
+
+    for raw in source.lines() {
+        let Some(parsed) = parse_line(raw) else {
+            continue;
+        };
+        match (parsed.label, parsed.mnemonic.as_deref()) {
+            (Some(name), Some("EQU")) => {
+                let value: u16 = parsed.operands[0].parse().unwrap_or(0);
+                program.symbols.insert(name, value);
+            }
+            (_, Some("ORG")) => {}
+            (Some(name), None) => {
+                if name == "@" {
+                    program.anon_labels.push(program.instructions.len());
+                } else {
+                    program.labels.insert(name, program.instructions.len());
+                }
+            }
+            (Some(name), Some(_)) => {
+                if name == "@" {
+                    program.anon_labels.push(program.instructions.len());
+                } else {
+                    program.labels.insert(name, program.instructions.len());
+                }
+                program.instructions.push(SourceLine {
+                    label: None,
+                    mnemonic: parsed.mnemonic,
+                    operands: parsed.operands,
+                });
+            }
+            (None, Some(_)) => program.instructions.push(SourceLine {
+                label: None,
+                mnemonic: parsed.mnemonic,
+                operands: parsed.operands,
+            }),
+            (None, None) => {}
+        }
+    }
+
+    program
+}
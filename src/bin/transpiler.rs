@@ -1,45 +1,316 @@
-#![feature(try_trait)]
-
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::env;
 use std::fmt;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::option::NoneError;
-use std::str::FromStr;
+use std::process;
 
 const FILENAME: &str = "output.asm";
 
-#[derive(Debug)]
-enum AsmLineError {
-    UnknownError,
-    UnknownOpcode(String),
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AsmLineErrorKind {
+    UnknownOpcode,
     IncorrectNumberOfArguments,
     EmptyArgument,
-    MalformedArgumentName(String),
-    MalformedRegisterName(String),
+    MalformedArgumentName,
+    MalformedRegisterName,
+    BadIntegerLiteral,
+    TruncatedSumAddress,
+    EmptyLine,
+    UnterminatedMacro,
+    MacroArityMismatch,
+    MacroRecursionLimit,
+}
+
+/// A parse failure pinned to the line and byte span that caused it, so it
+/// can be reported with a caret under the offending token instead of just
+/// aborting the whole run.
+#[derive(Debug)]
+struct AsmLineError {
+    line: usize,
+    text: String,
+    span: (usize, usize),
+    kind: AsmLineErrorKind,
+    detail: Option<String>,
+}
+
+impl AsmLineError {
+    fn new(line: usize, text: &str, span: (usize, usize), kind: AsmLineErrorKind) -> Self {
+        AsmLineError {
+            line,
+            text: text.to_string(),
+            span,
+            kind,
+            detail: None,
+        }
+    }
+
+    fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+impl fmt::Display for AsmLineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = match self.kind {
+            AsmLineErrorKind::UnknownOpcode => format!(
+                "unknown opcode '{}'",
+                self.detail.as_deref().unwrap_or("?")
+            ),
+            AsmLineErrorKind::IncorrectNumberOfArguments => "incorrect number of arguments".to_string(),
+            AsmLineErrorKind::EmptyArgument => "empty argument".to_string(),
+            AsmLineErrorKind::MalformedArgumentName => format!(
+                "malformed argument '{}'",
+                self.detail.as_deref().unwrap_or("?")
+            ),
+            AsmLineErrorKind::MalformedRegisterName => format!(
+                "unknown register '{}'",
+                self.detail.as_deref().unwrap_or("?")
+            ),
+            AsmLineErrorKind::BadIntegerLiteral => "invalid integer literal".to_string(),
+            AsmLineErrorKind::TruncatedSumAddress => "truncated sum address".to_string(),
+            AsmLineErrorKind::EmptyLine => "empty line".to_string(),
+            AsmLineErrorKind::UnterminatedMacro => format!(
+                "unterminated '.macro {}' (missing '.endm')",
+                self.detail.as_deref().unwrap_or("?")
+            ),
+            AsmLineErrorKind::MacroArityMismatch => self
+                .detail
+                .clone()
+                .unwrap_or_else(|| "macro arity mismatch".to_string()),
+            AsmLineErrorKind::MacroRecursionLimit => format!(
+                "macro expansion of '{}' exceeded the recursion limit",
+                self.detail.as_deref().unwrap_or("?")
+            ),
+        };
+
+        writeln!(f, "error: {} (line {})", message, self.line + 1)?;
+        writeln!(f, "    {}", self.text)?;
+        let (start, end) = self.span;
+        let caret_len = end.saturating_sub(start).max(1);
+        writeln!(f, "    {}{}", " ".repeat(start), "^".repeat(caret_len))
+    }
+}
+
+/// Splits a line on whitespace like `split_whitespace`, but keeps the byte
+/// span of each token so parse errors can point at the exact column.
+fn tokenize(line: &str) -> Vec<(usize, usize, String)> {
+    let mut tokens = vec![];
+    let mut start: Option<usize> = None;
+    for (i, c) in line.char_indices() {
+        if c.is_whitespace() {
+            if let Some(s) = start.take() {
+                tokens.push((s, i, line[s..i].to_string()));
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        tokens.push((s, line.len(), line[s..].to_string()));
+    }
+    tokens
+}
+
+/// Limits `.macro` expansion depth so a macro that (directly or through a
+/// chain of others) invokes itself fails with a diagnostic instead of
+/// looping forever.
+const MACRO_EXPANSION_LIMIT: usize = 16;
+
+struct MacroDef {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+/// Strips `.macro NAME args... / .endm` definitions out of the input and
+/// expands every invocation of a defined macro by substituting positional
+/// arguments into its body, recursively, before handing the result to
+/// `AsmLine::parse`. Lines that don't name a known macro pass through
+/// untouched -- unknown opcodes are still the parser's problem to report.
+fn expand_macros(lines: &[String]) -> Result<Vec<String>, AsmLineError> {
+    let mut macros: HashMap<String, MacroDef> = HashMap::new();
+    let mut rest: Vec<(usize, String)> = vec![];
+
+    let mut iter = lines.iter().enumerate();
+    while let Some((line_no, line)) = iter.next() {
+        if let Some(header) = line.trim().strip_prefix(".macro ") {
+            let parts: Vec<&str> = header.split_whitespace().collect();
+            let name = parts[0].to_string();
+            let params = parts[1..]
+                .iter()
+                .map(|s| s.trim_end_matches(',').to_string())
+                .collect();
+
+            let mut body = vec![];
+            loop {
+                match iter.next() {
+                    Some((_, body_line)) if body_line.trim() == ".endm" => break,
+                    Some((_, body_line)) => body.push(body_line.clone()),
+                    None => {
+                        return Err(AsmLineError::new(
+                            line_no,
+                            line,
+                            (0, line.len()),
+                            AsmLineErrorKind::UnterminatedMacro,
+                        )
+                        .with_detail(name));
+                    }
+                }
+            }
+            macros.insert(name, MacroDef { params, body });
+        } else {
+            rest.push((line_no, line.clone()));
+        }
+    }
+
+    let mut expanded = vec![];
+    for (line_no, line) in rest {
+        expand_line(&macros, &line, line_no, 0, &mut expanded)?;
+    }
+    Ok(expanded)
 }
 
-impl From<NoneError> for AsmLineError {
-    fn from(_: NoneError) -> Self {
-        AsmLineError::UnknownError
+fn expand_line(
+    macros: &HashMap<String, MacroDef>,
+    line: &str,
+    line_no: usize,
+    depth: usize,
+    out: &mut Vec<String>,
+) -> Result<(), AsmLineError> {
+    let trimmed = line.trim();
+    let mut parts = trimmed.split_whitespace();
+    let head = match parts.next() {
+        Some(h) => h,
+        None => {
+            out.push(line.to_string());
+            return Ok(());
+        }
+    };
+
+    let def = match macros.get(head) {
+        Some(def) => def,
+        None => {
+            out.push(line.to_string());
+            return Ok(());
+        }
+    };
+
+    if depth >= MACRO_EXPANSION_LIMIT {
+        return Err(AsmLineError::new(
+            line_no,
+            line,
+            (0, line.len()),
+            AsmLineErrorKind::MacroRecursionLimit,
+        )
+        .with_detail(head.to_string()));
+    }
+
+    let args: Vec<&str> = parts
+        .flat_map(|p| p.split(','))
+        .filter(|a| !a.is_empty())
+        .collect();
+    if args.len() != def.params.len() {
+        return Err(AsmLineError::new(
+            line_no,
+            line,
+            (0, line.len()),
+            AsmLineErrorKind::MacroArityMismatch,
+        )
+        .with_detail(format!(
+            "macro '{}' expects {} argument(s), got {}",
+            head,
+            def.params.len(),
+            args.len()
+        )));
+    }
+
+    for body_line in &def.body {
+        let mut substituted = body_line.clone();
+        for (param, arg) in def.params.iter().zip(args.iter()) {
+            substituted = substitute_param(&substituted, param, arg);
+        }
+        expand_line(macros, &substituted, line_no, depth + 1, out)?;
+    }
+    Ok(())
+}
+
+/// Strips the `%` register sigil a token may carry, so a bare param name
+/// (`r`) matches the way it's actually written at its use site (`%r`).
+fn strip_sigil(s: &str) -> &str {
+    s.strip_prefix('%').unwrap_or(s)
+}
+
+/// Replaces whole occurrences of `param` with `arg` in `line`, splitting on
+/// whitespace and commas the same way the macro invocation's own argument
+/// list is split. Using `str::replace` here would corrupt the expansion
+/// whenever one param name is a substring of another token (a one-letter
+/// param `r` matching inside `addr`, say) -- this only ever replaces a
+/// token that matches `param` exactly, modulo an optional leading `%` on
+/// either side, since every real operand in this dialect is written with
+/// the sigil (`incb %r`) even though `.macro` headers name params without it.
+fn substitute_param(line: &str, param: &str, arg: &str) -> String {
+    let bare_param = strip_sigil(param);
+    let mut out = String::new();
+    let mut token = String::new();
+    for c in line.chars() {
+        if c.is_whitespace() || c == ',' {
+            out.push_str(if strip_sigil(&token) == bare_param { arg } else { &token });
+            token.clear();
+            out.push(c);
+        } else {
+            token.push(c);
+        }
+    }
+    out.push_str(if strip_sigil(&token) == bare_param { arg } else { &token });
+    out
+}
+
+#[cfg(test)]
+mod macro_tests {
+    use super::*;
+
+    fn lines(src: &str) -> Vec<String> {
+        src.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn reports_arity_mismatch() {
+        let src = lines(".macro inc1 r\n\tincb %r\n.endm\ninc1 %eax, %ecx\n");
+        let err = expand_macros(&src).unwrap_err();
+        assert_eq!(err.kind, AsmLineErrorKind::MacroArityMismatch);
+    }
+
+    #[test]
+    fn reports_macro_recursion_limit() {
+        let src = lines(".macro loop\n\tloop\n.endm\nloop\n");
+        let err = expand_macros(&src).unwrap_err();
+        assert_eq!(err.kind, AsmLineErrorKind::MacroRecursionLimit);
+    }
+
+    #[test]
+    fn expands_substituting_the_percent_prefixed_register() {
+        let src = lines(".macro inc1 r\n\tincb %r\n.endm\ninc1 %eax\n");
+        let expanded = expand_macros(&src).unwrap();
+        assert_eq!(expanded, vec!["\tincb %eax".to_string()]);
     }
 }
 
 macro_rules! opcode_with_2_args {
-    ($parts:expr, $opcode:path) => {
-        return AsmLine::args($parts, 2).and_then(|args| {
+    ($parts:expr, $opcode:path, $line_no:expr, $raw:expr) => {
+        return AsmLine::args($parts, 2, $line_no, $raw).and_then(|args| {
             Ok($opcode(
-                args[0].parse::<Arg>().unwrap(),
-                args[1].parse::<Arg>().unwrap(),
+                Arg::parse($line_no, $raw, args[0].0, &args[0].2)?,
+                Arg::parse($line_no, $raw, args[1].0, &args[1].2)?,
             ))
-        });
+        })
     };
 }
 
 macro_rules! opcode_with_1_arg {
-    ($parts:expr, $opcode:path) => {
-        return AsmLine::args($parts, 1)
-            .and_then(|args| Ok($opcode(args[0].parse::<Arg>().unwrap())));
+    ($parts:expr, $opcode:path, $line_no:expr, $raw:expr) => {
+        return AsmLine::args($parts, 1, $line_no, $raw)
+            .and_then(|args| Ok($opcode(Arg::parse($line_no, $raw, args[0].0, &args[0].2)?)))
     };
 }
 
@@ -55,362 +326,827 @@ enum Arg {
 }
 
 impl Arg {
-    fn register_from_name(name: &str) -> Result<char, AsmLineError> {
+    fn register_from_name(
+        line_no: usize,
+        raw: &str,
+        span: (usize, usize),
+        name: &str,
+    ) -> Result<char, AsmLineError> {
         match name {
             "eax" | "al" => Ok('A'),
             "ecx" | "cl" => Ok('C'),
             "edx" | "dl" => Ok('D'),
-            _ => Err(AsmLineError::MalformedRegisterName(name.to_string())),
+            _ => Err(AsmLineError::new(
+                line_no,
+                raw,
+                span,
+                AsmLineErrorKind::MalformedRegisterName,
+            )
+            .with_detail(name.to_string())),
         }
     }
-}
 
-impl fmt::Display for Arg {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::Label(s) => write!(f, "{}", s),
-            Self::Accumulator => write!(f, "A"),
-            _ => write!(f, "Unable to generate 6502 code for argument: {:?}", self),
-        }
-    }
-}
-
-impl PartialEq for Arg {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Accumulator, Self::Accumulator) => true,
-            _ => false,
-        }
-    }
-}
-
-impl FromStr for Arg {
-    type Err = AsmLineError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+    /// Parses a single argument token found at byte offset `col` within
+    /// `raw` (line `line_no`), so any failure carries a precise span.
+    fn parse(line_no: usize, raw: &str, col: usize, s: &str) -> Result<Self, AsmLineError> {
         if s.is_empty() {
-            return Err(AsmLineError::EmptyArgument);
+            return Err(AsmLineError::new(
+                line_no,
+                raw,
+                (col, col),
+                AsmLineErrorKind::EmptyArgument,
+            ));
         }
 
+        let span = (col, col + s.len());
         let mut it = s.chars().peekable();
         if let Some(c) = it.peek() {
-            Ok(match c {
-                '%' => Arg::register_from_name(
-                    &it.skip(1)
-                        .filter(|c| !vec![',', '%'].contains(c))
-                        .collect::<String>(),
-                )
-                .and_then(|c| match c {
-                    'A' => Ok(Self::Accumulator),
-                    _ => Ok(Self::VirtualRegister(c)),
-                }),
-                '.' => Ok(Self::Label({
-                    it.skip(1).filter(|c| *c != ',').collect::<String>()
-                })),
+            match c {
+                '%' => {
+                    let name: String = it
+                        .skip(1)
+                        .filter(|c| ![',', '%'].contains(c))
+                        .collect();
+                    let reg = Arg::register_from_name(line_no, raw, span, &name)?;
+                    Ok(match reg {
+                        'A' => Self::Accumulator,
+                        _ => Self::VirtualRegister(reg),
+                    })
+                }
+                '.' => Ok(Self::Label(
+                    it.skip(1).filter(|c| *c != ',').collect::<String>(),
+                )),
                 '(' => {
                     let args: String = it.collect();
-                    let args = args.trim_end_matches(")");
-                    let args = args.trim_start_matches("(");
-                    let args: Vec<String> = args.split(",").map(ToString::to_string).collect();
-
-                    // TODO: Simplification: edx => D, ecx => C, etc.
-                    Ok(Self::SumAddress(
-                        args[0].chars().skip(2).next().unwrap(),
-                        args[1].chars().skip(2).next().unwrap(),
-                    ))
+                    let args = args.trim_end_matches(')');
+                    let args = args.trim_start_matches('(');
+                    let args: Vec<String> = args.split(',').map(ToString::to_string).collect();
+
+                    let reg_at = |i: usize| -> Result<char, AsmLineError> {
+                        args.get(i)
+                            .and_then(|a| a.chars().nth(2))
+                            .ok_or_else(|| {
+                                AsmLineError::new(
+                                    line_no,
+                                    raw,
+                                    span,
+                                    AsmLineErrorKind::TruncatedSumAddress,
+                                )
+                            })
+                    };
+                    Ok(Self::SumAddress(reg_at(0)?, reg_at(1)?))
                 }
-                '0'..='9' => Ok(Self::AbsoluteAddress({
-                    it.filter(|c| *c != ',')
-                        .collect::<String>()
+                '0'..='9' => {
+                    let digits: String = it.filter(|c| *c != ',').collect();
+                    digits
                         .parse::<i32>()
-                        .unwrap()
-                })),
-                '$' => Ok(Self::Literal({
-                    it.skip(1)
-                        .filter(|c| *c != ',')
-                        .collect::<String>()
-                        .parse::<i32>()
-                        .unwrap()
-                })),
-                _ => Err(AsmLineError::MalformedArgumentName(s.to_string())),
-            }?)
+                        .map(Self::AbsoluteAddress)
+                        .map_err(|_| {
+                            AsmLineError::new(line_no, raw, span, AsmLineErrorKind::BadIntegerLiteral)
+                        })
+                }
+                '$' => {
+                    let digits: String = it.skip(1).filter(|c| *c != ',').collect();
+                    digits.parse::<i32>().map(Self::Literal).map_err(|_| {
+                        AsmLineError::new(line_no, raw, span, AsmLineErrorKind::BadIntegerLiteral)
+                    })
+                }
+                _ => Err(AsmLineError::new(
+                    line_no,
+                    raw,
+                    span,
+                    AsmLineErrorKind::MalformedArgumentName,
+                )
+                .with_detail(s.to_string())),
+            }
         } else {
-            return Err(AsmLineError::UnknownError);
+            Err(AsmLineError::new(
+                line_no,
+                raw,
+                span,
+                AsmLineErrorKind::EmptyArgument,
+            ))
         }
     }
 }
 
-#[derive(Debug)]
-enum AsmLine {
-    Label(String),
-    Xor(Arg, Arg),
-    Adc(Arg, Arg),
-    Mov(Arg, Arg),
-    MovZ(Arg, Arg),
-    Inc(Arg),
-    Dec(Arg),
-    Jmp(Arg),
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Label(s) => write!(f, "{}", s),
+            Self::Accumulator => write!(f, "A"),
+            _ => write!(f, "Unable to generate 6502 code for argument: {:?}", self),
+        }
+    }
 }
 
 impl AsmLine {
-    fn args<'a, I>(parts: I, expected_count: usize) -> Result<Vec<String>, AsmLineError>
-    where
-        I: IntoIterator<Item = &'a String>,
-    {
-        let mut args: Vec<String> = vec![];
+    fn args(
+        parts: &[(usize, usize, String)],
+        expected_count: usize,
+        line_no: usize,
+        raw: &str,
+    ) -> Result<Vec<(usize, usize, String)>, AsmLineError> {
+        let mut args: Vec<(usize, usize, String)> = vec![];
 
-        let mut i = parts.into_iter();
+        let mut i = parts.iter();
         for _ in 0..2 {
-            for first in i.next() {
-                if first.starts_with("(") {
-                    i.next()
-                        .and_then(|second| Some(args.push(format!("{}{}", first, second))));
+            if let Some((s1, e1, first)) = i.next() {
+                if first.starts_with('(') {
+                    if let Some((_, e2, second)) = i.next() {
+                        args.push((*s1, *e2, format!("{}{}", first, second)));
+                    }
                 } else {
-                    args.push(first.to_owned());
+                    args.push((*s1, *e1, first.clone()));
                 }
             }
         }
 
-        if args.len() == expected_count {
+        if args.len() == expected_count && i.next().is_none() {
             Ok(args)
         } else {
-            Err(AsmLineError::IncorrectNumberOfArguments)
+            Err(AsmLineError::new(
+                line_no,
+                raw,
+                (0, raw.len()),
+                AsmLineErrorKind::IncorrectNumberOfArguments,
+            ))
         }
     }
 }
 
-impl FromStr for AsmLine {
-    type Err = AsmLineError;
+// The `AsmLine` enum, its `parse` dispatch and its `Display` emitter are
+// generated from `instructions.in` by build.rs -- see that file to add or
+// change an opcode instead of editing the match arms below by hand.
+include!(concat!(env!("OUT_DIR"), "/instrs.rs"));
+
+/// A small interpreter for the 6502 text this backend emits, driven by
+/// `--run`. It only understands the mnemonics the `Display` impl above
+/// actually produces (plus the Atari-style `MWA`/`ADW`/`SBW`/`DEW` word
+/// pseudo-ops), and treats labels as indices into the instruction list
+/// rather than resolving real memory addresses for code -- there's no
+/// need for byte-accurate assembly when the only consumer is this test
+/// harness checking final register/memory state. Anonymous `@`/`@+`
+/// labels (as emitted by the 16-bit `cmpb`/`cmpl` rule) resolve to the
+/// next `@` occurrence after the referencing instruction.
+mod sim {
+    use std::collections::HashMap;
+
+    // Parsing the plain-text source into a `Program` (`SourceLine`,
+    // `parse_line`, `assemble`) is `include!`d from `sim_core.rs`, shared
+    // with the modular `transpiler/sim.rs` binary -- only `Cpu` stays
+    // here, since the two binaries' memory models differ.
+    include!("sim_core.rs");
+
+    #[derive(Debug, Clone, Default)]
+    pub struct Cpu {
+        pub a: u8,
+        pub x: u8,
+        pub y: u8,
+        pub sp: u8,
+        pub carry: bool,
+        pub zero: bool,
+        pub pc: usize,
+        pub memory: Vec<u8>,
+        call_stack: Vec<usize>,
+    }
+
+    impl Cpu {
+        pub fn new() -> Self {
+            Cpu {
+                sp: 0xFF,
+                memory: vec![0; 0x1_0000],
+                ..Default::default()
+            }
+        }
+
+        fn resolve_address(&self, symbols: &HashMap<String, u16>, token: &str) -> u16 {
+            let (base, offset) = match token.split_once('+') {
+                Some((base, offset)) => (base, offset.parse::<u16>().unwrap_or(0)),
+                None => (token, 0),
+            };
+            let base = symbols
+                .get(base)
+                .copied()
+                .unwrap_or_else(|| base.parse().unwrap_or(0));
+            base.wrapping_add(offset)
+        }
+
+        fn resolve_immediate(&self, symbols: &HashMap<String, u16>, token: &str) -> i32 {
+            symbols
+                .get(token)
+                .map(|v| *v as i32)
+                .unwrap_or_else(|| token.parse().unwrap_or(0))
+        }
+
+        fn resolve_value(&self, symbols: &HashMap<String, u16>, operand: &str) -> u8 {
+            if let Some(rest) = operand.strip_prefix("#<") {
+                (self.resolve_immediate(symbols, rest) & 0xFF) as u8
+            } else if let Some(rest) = operand.strip_prefix("#>") {
+                ((self.resolve_immediate(symbols, rest) >> 8) & 0xFF) as u8
+            } else if let Some(rest) = operand.strip_prefix('#') {
+                (self.resolve_immediate(symbols, rest) & 0xFF) as u8
+            } else {
+                let addr = self.resolve_address(symbols, operand);
+                self.memory[addr as usize]
+            }
+        }
+
+        fn word_value(&self, symbols: &HashMap<String, u16>, operand: &str) -> u16 {
+            if let Some(rest) = operand.strip_prefix('#') {
+                self.resolve_immediate(symbols, rest) as u16
+            } else {
+                let addr = self.resolve_address(symbols, operand) as usize;
+                self.memory[addr] as u16 | ((self.memory[addr + 1] as u16) << 8)
+            }
+        }
+
+        fn store_word(&mut self, addr: u16, value: u16) {
+            self.memory[addr as usize] = (value & 0xFF) as u8;
+            self.memory[addr as usize + 1] = ((value >> 8) & 0xFF) as u8;
+        }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let line = s.to_string();
+        fn indirect_y_address(&self, symbols: &HashMap<String, u16>, operand: &str) -> Option<u16> {
+            let inner = operand.strip_suffix(",y")?.strip_prefix('(')?.strip_suffix(')')?;
+            let base = self.resolve_address(symbols, inner);
+            Some(base.wrapping_add(self.y as u16))
+        }
 
-        if line.starts_with('.') {
-            return Ok(Self::Label(line[1..].to_string()));
+        /// Resolves a branch/jump operand to an instruction index. `@+`
+        /// means "the next anonymous label after this instruction";
+        /// anything else is a named label.
+        fn resolve_target(&self, program: &Program, operand: &str) -> usize {
+            if operand == "@+" {
+                program
+                    .anon_labels
+                    .iter()
+                    .find(|&&pos| pos > self.pc)
+                    .copied()
+                    .unwrap_or(program.instructions.len())
+            } else {
+                program
+                    .labels
+                    .get(operand)
+                    .copied()
+                    .unwrap_or(program.instructions.len())
+            }
         }
 
-        let parts: Vec<String> = line.split_whitespace().map(ToString::to_string).collect();
+        /// Executes one instruction and reports whether the program can
+        /// keep running (`false` once `pc` runs off the end, i.e. halt).
+        pub fn step(&mut self, program: &Program) -> bool {
+            if self.pc >= program.instructions.len() {
+                return false;
+            }
+            let line = program.instructions[self.pc].clone();
+            let ops = &line.operands;
+            let mut next_pc = self.pc + 1;
 
-        let mut iter = parts.iter();
-        if let Some(opcode) = iter.next() {
-            match opcode.as_str() {
-                "movb" | "movl" => opcode_with_2_args!(iter, Self::Mov),
-                "movzbl" => opcode_with_2_args!(iter, Self::MovZ),
-                "xorl" => opcode_with_2_args!(iter, Self::Xor),
-                "addb" => opcode_with_2_args!(iter, Self::Adc),
-                "incb" => opcode_with_1_arg!(iter, Self::Inc),
-                "decb" => opcode_with_1_arg!(iter, Self::Dec),
-                "jmp" => opcode_with_1_arg!(iter, Self::Jmp),
-                _ => return Err(AsmLineError::UnknownOpcode(opcode.to_string())),
+            match line.mnemonic.as_deref().unwrap_or("") {
+                "LDA" => self.a = self.resolve_value(&program.symbols, &ops[0]),
+                "STA" => {
+                    let addr = self
+                        .indirect_y_address(&program.symbols, &ops[0])
+                        .unwrap_or_else(|| self.resolve_address(&program.symbols, &ops[0]));
+                    self.memory[addr as usize] = self.a;
+                }
+                "ADC" => {
+                    let rhs = self.resolve_value(&program.symbols, &ops[0]);
+                    let sum = self.a as u16 + rhs as u16 + self.carry as u16;
+                    self.carry = sum > 0xFF;
+                    self.a = sum as u8;
+                    self.zero = self.a == 0;
+                }
+                "SBC" => {
+                    let rhs = self.resolve_value(&program.symbols, &ops[0]);
+                    let diff = self.a as i16 - rhs as i16 - (1 - self.carry as i16);
+                    self.carry = diff >= 0;
+                    self.a = diff as u8;
+                    self.zero = self.a == 0;
+                }
+                "CMP" => {
+                    let rhs = self.resolve_value(&program.symbols, &ops[0]);
+                    self.zero = self.a == rhs;
+                    self.carry = self.a >= rhs;
+                }
+                "EOR" => {
+                    let rhs = self.resolve_value(&program.symbols, &ops[0]);
+                    self.a ^= rhs;
+                    self.zero = self.a == 0;
+                }
+                "CLC" => self.carry = false,
+                "SEC" => self.carry = true,
+                "INC" => {
+                    let addr = self.resolve_address(&program.symbols, &ops[0]);
+                    self.memory[addr as usize] = self.memory[addr as usize].wrapping_add(1);
+                    self.zero = self.memory[addr as usize] == 0;
+                }
+                "DEC" => {
+                    let addr = self.resolve_address(&program.symbols, &ops[0]);
+                    self.memory[addr as usize] = self.memory[addr as usize].wrapping_sub(1);
+                    self.zero = self.memory[addr as usize] == 0;
+                }
+                "JMP" => next_pc = self.resolve_target(program, &ops[0]),
+                "BEQ" if self.zero => next_pc = self.resolve_target(program, &ops[0]),
+                "BNE" if !self.zero => next_pc = self.resolve_target(program, &ops[0]),
+                "BCC" if !self.carry => next_pc = self.resolve_target(program, &ops[0]),
+                "BCS" if self.carry => next_pc = self.resolve_target(program, &ops[0]),
+                "JSR" => {
+                    self.call_stack.push(next_pc);
+                    next_pc = self.resolve_target(program, &ops[0]);
+                }
+                "RTS" => next_pc = self.call_stack.pop().unwrap_or(program.instructions.len()),
+                "PHA" => {
+                    self.memory[0x100 + self.sp as usize] = self.a;
+                    self.sp = self.sp.wrapping_sub(1);
+                }
+                "PLA" => {
+                    self.sp = self.sp.wrapping_add(1);
+                    self.a = self.memory[0x100 + self.sp as usize];
+                }
+                "TYA" => self.a = self.y,
+                "TAY" => self.y = self.a,
+                "LDY" => self.y = self.resolve_value(&program.symbols, &ops[0]),
+                "MWA" => {
+                    let value = self.word_value(&program.symbols, &ops[0]);
+                    let dst = self.resolve_address(&program.symbols, &ops[1]);
+                    self.store_word(dst, value);
+                }
+                "ADW" => {
+                    let dst = self.resolve_address(&program.symbols, &ops[0]);
+                    let rhs = self.word_value(&program.symbols, &ops[1]);
+                    let cur = self.word_value(&program.symbols, &ops[0]);
+                    self.store_word(dst, cur.wrapping_add(rhs));
+                }
+                "SBW" => {
+                    let dst = self.resolve_address(&program.symbols, &ops[0]);
+                    let rhs = self.word_value(&program.symbols, &ops[1]);
+                    let cur = self.word_value(&program.symbols, &ops[0]);
+                    self.store_word(dst, cur.wrapping_sub(rhs));
+                }
+                "DEW" => {
+                    let dst = self.resolve_address(&program.symbols, &ops[0]);
+                    let cur = self.word_value(&program.symbols, &ops[0]);
+                    self.store_word(dst, cur.wrapping_sub(1));
+                }
+                _ => {}
             }
+
+            self.pc = next_pc;
+            true
         }
+    }
 
-        Err(AsmLineError::UnknownError)
+    /// Wraps a `Cpu` with breakpoints and optional instruction tracing, so
+    /// a generated program can be run to completion (or to a breakpoint)
+    /// and then inspected instead of eyeballed.
+    pub struct Debugger {
+        pub cpu: Cpu,
+        pub program: Program,
+        pub trace_only: bool,
+        breakpoints: Vec<usize>,
     }
-}
 
-impl fmt::Display for AsmLine {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::Label(l) => writeln!(f, "{}", l),
-            Self::Jmp(l) => writeln!(f, "\tJMP {}", l),
-            Self::Xor(l, r) if l == r => writeln!(f, "\tLD{} #0", l),
-            Self::Adc(l, r) => match (l, r) {
-                (Arg::AbsoluteAddress(a), Arg::Accumulator) => {
-                    writeln!(f,
-                        "\tCLC\n\
-                         \tADC {}"
-                         ,a)
-                },
-                (Arg::Literal(l), Arg::VirtualRegister(r)) if l < &0i32  => {
-                    writeln!(f,
-                        "\tPHA\n\
-                         \tSBW VREG_{reg} #{literal}\n\
-                         \tPLA"
-                         , reg=r, literal=-l)
-                },
-                _ => writeln!(f, "Unable to generate code for opcode 'ADC' with combination of arguments: '{:?}' and '{:?}'", l, r),
-            },
-            Self::MovZ(l, r) => match (l, r) {
-                (Arg::VirtualRegister(l), Arg::VirtualRegister(r)) if l == r => {
-                    // Do nothing
-                    Ok(())
-                },
-                (Arg::Accumulator, Arg::VirtualRegister(r)) => {
-                    writeln!(f,
-                        "\tSTA VREG_{reg}\n\
-                         \tPHA\n\
-                         \tLDA #0\n\
-                         \tSTA VREG_{reg}+1\n\
-                         \tPLA"
-                         , reg=r)
-                },
-                _ => writeln!(f, "Unable to generate code for opcode 'MOVZ' with combination of arguments: '{:?}' and '{:?}'", l, r),
-            },
-            Self::Mov(l, r) => match (l, r) {
-                (Arg::Literal(l), Arg::SumAddress(x, y)) => {
-                    writeln!(f,
-                        "\tPHA\n\
-                         \tTYA\n\
-                         \tPHA\n\
-                         \tMWA VREG_{op1} TMPW\n\
-                         \tADW TMPW VREG_{op2}\n\
-                         \tLDY #0\n\
-                         \tLDA #{literal}\n\
-                         \tSTA (TMPW),y\n\
-                         \tPLA\n\
-                         \tTAY\n\
-                         \tPLA"
-                         , literal=l, op1=x, op2=y)
-                },
-                (Arg::Literal(l), Arg::AbsoluteAddress(a)) => {
-                    writeln!(f,
-                        "\tPHA\n\
-                         \tLDA #{literal}\n\
-                         \tSTA {addr}\n\
-                         \tPLA"
-                         , literal=l, addr=a)
-                },
-                (Arg::Literal(l), Arg::Accumulator) => {
-                    writeln!(f,
-                        "\tLDA #{literal}"
-                         , literal=l)
-                },
-                (Arg::Accumulator, Arg::AbsoluteAddress(a)) => {
-                    writeln!(f, "\tSTA {}", a)
-                },
-                (Arg::AbsoluteAddress(a), Arg::Accumulator) => {
-                    writeln!(f, "\tLDA {}", a)
-                },
-                (Arg::Literal(l), Arg::VirtualRegister(r)) => {
-                    writeln!(f,
-                        "\tPHA\n\
-                         \tMWA #{literal} VREG_{reg}\n\
-                         \tPLA"
-                         , literal=l, reg=r)
-                },
-                (Arg::Accumulator, Arg::VirtualRegister(r)) => {
-                    writeln!(f,
-                        "\tPHA\n\
-                         \tSTA VREG_{reg}\n\
-                         \tLDA #0\n\
-                         \tSTA VREG_{reg}+1\n\
-                         \tPLA"
-                         , reg=r)
-                },
-                (Arg::AbsoluteAddress(a), Arg::VirtualRegister(r)) => {
-                    writeln!(f,
-                        "\tPHA\n\
-                         \tLDA {addr}\n\
-                         \tSTA VREG_{reg}\n\
-                         \tPLA"
-                         , addr=a, reg=r)
-                },
-                (Arg::VirtualRegister(r), Arg::AbsoluteAddress(a)) => {
-                    writeln!(f,
-                        "\tPHA\n\
-                         \tLDA VREG_{reg}\n\
-                         \tSTA {addr}\n\
-                         \tPLA"
-                         , addr=a, reg=r)
-                },
-                _ => writeln!(f, "Unable to generate code for opcode 'MOV' with combination of arguments: '{:?}' and '{:?}'", l, r),
-            },
-            Self::Inc(a) => {
-                match a {
-                    Arg::Accumulator =>{
-                        writeln!(f, "\tCLC\n\tADC #1")
-                    }
-                    _ => writeln!(f, "Unable to generate code for opcode 'INC' with argument: '{:?}'", a),
+    impl Debugger {
+        pub fn new(program: Program) -> Self {
+            Debugger {
+                cpu: Cpu::new(),
+                program,
+                trace_only: false,
+                breakpoints: vec![],
+            }
+        }
+
+        pub fn break_at_label(&mut self, label: &str) {
+            if let Some(index) = self.program.labels.get(label) {
+                self.breakpoints.push(*index);
+            }
+        }
+
+        pub fn break_at(&mut self, index: usize) {
+            self.breakpoints.push(index);
+        }
+
+        /// Single-steps `count` instructions, or until halted.
+        pub fn step(&mut self, count: usize) -> bool {
+            for _ in 0..count {
+                if self.trace_only {
+                    eprintln!(
+                        "{:4} {:?}  A={:02X} X={:02X} Y={:02X} C={} Z={}",
+                        self.cpu.pc,
+                        self.program.instructions.get(self.cpu.pc),
+                        self.cpu.a,
+                        self.cpu.x,
+                        self.cpu.y,
+                        self.cpu.carry as u8,
+                        self.cpu.zero as u8,
+                    );
+                }
+                if !self.cpu.step(&self.program) {
+                    return false;
                 }
             }
-            Self::Dec(a) => {
-                match a {
-                    Arg::Accumulator =>{
-                        writeln!(f, "\tSEC\n\tSBC #1")
-                    }
-                    Arg::VirtualRegister(r) =>{
-                        writeln!(f, "\tDEW VREG_{reg}", reg=r)
-                    }
-                    _ => writeln!(f, "Unable to generate code for opcode 'DEC' with argument: '{:?}'", a),
+            true
+        }
+
+        /// Runs until a breakpoint is hit, the program halts, or
+        /// `step_budget` instructions have executed (a runaway-loop guard).
+        pub fn run(&mut self, step_budget: usize) -> bool {
+            for _ in 0..step_budget {
+                if self.breakpoints.contains(&self.cpu.pc) {
+                    return true;
+                }
+                if !self.step(1) {
+                    return false;
                 }
             }
-            _ => writeln!(f, "Unable to generate 6502 code for line: {:?}", self),
+            true
         }
-    }
-}
 
-#[derive(Debug)]
-struct Transpiler {
-    pub vregs: HashSet<char>,
-}
+        pub fn dump_registers(&self) -> String {
+            format!(
+                "A={:02X} X={:02X} Y={:02X} SP={:02X} C={} Z={}",
+                self.cpu.a, self.cpu.x, self.cpu.y, self.cpu.sp, self.cpu.carry as u8, self.cpu.zero as u8
+            )
+        }
 
-impl Transpiler {
-    fn add_vreg(&mut self, r: char) {
-        self.vregs.insert(r);
+        pub fn dump_memory(&self, addr: u16, len: u16) -> String {
+            (addr..addr + len)
+                .map(|a| format!("{:02X}", self.cpu.memory[a as usize]))
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
     }
 
-    fn insert_if_is_virtual_register(&mut self, arg: &Arg) {
-        if let Arg::VirtualRegister(r) = arg {
-            self.add_vreg(*r)
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn run_source(source: &str) -> Cpu {
+            let program = assemble(source);
+            let mut cpu = Cpu::new();
+            for _ in 0..1_000 {
+                if !cpu.step(&program) {
+                    break;
+                }
+            }
+            cpu
+        }
+
+        #[test]
+        fn adc_accumulates_into_a() {
+            let cpu = run_source("\tLDA #5\n\tADC #3\n");
+            assert_eq!(cpu.a, 8);
+        }
+
+        #[test]
+        fn cmp_sets_zero_and_carry_on_equal() {
+            let cpu = run_source("\tLDA #5\n\tCMP #5\n");
+            assert!(cpu.zero);
+            assert!(cpu.carry);
+        }
+
+        #[test]
+        fn cmp_clears_carry_when_accumulator_is_smaller() {
+            let cpu = run_source("\tLDA #3\n\tCMP #5\n");
+            assert!(!cpu.carry);
+        }
+
+        #[test]
+        fn beq_skips_the_next_instruction_on_equal() {
+            let cpu = run_source("\tLDA #5\n\tCMP #5\n\tBEQ SKIP\n\tLDA #99\nSKIP\n\tLDA #1\n");
+            assert_eq!(cpu.a, 1);
+        }
+
+        #[test]
+        fn sta_stores_the_accumulator_at_a_virtual_register() {
+            let cpu = run_source("VREG_A equ 128\n\tLDA #7\n\tSTA VREG_A\n");
+            assert_eq!(cpu.memory[128], 7);
+        }
+
+        #[test]
+        fn debugger_breakpoint_halts_before_target_label() {
+            let program = assemble("\tLDA #5\nSTOP\n\tLDA #99\n");
+            let mut debugger = Debugger::new(program);
+            debugger.break_at_label("STOP");
+            debugger.run(1_000);
+            assert_eq!(debugger.cpu.a, 5);
         }
     }
+}
 
-    fn check_for_virtual_registers(&mut self, asm_line: &AsmLine) {
-        match &asm_line {
-            AsmLine::Xor(arg1, arg2)
-            | AsmLine::Adc(arg1, arg2)
-            | AsmLine::Mov(arg1, arg2)
-            | AsmLine::MovZ(arg1, arg2) => {
-                self.insert_if_is_virtual_register(arg1);
-                self.insert_if_is_virtual_register(arg2);
-            }
-            AsmLine::Inc(arg) | AsmLine::Dec(arg) | AsmLine::Jmp(arg) => {
-                self.insert_if_is_virtual_register(arg);
+/// Exercises the `cmpb`/`cmpl`/`scmpb`/`scmpl` + `jcc` family end to end
+/// through `sim`, instead of just checking the generator compiles:
+/// assembles the real `Display` output for `Cmp`/`Scmp`/branch `AsmLine`s
+/// plus hand-written `SET_LAST_CMP`/`SET_SLAST_CMP` subroutines (normally
+/// emitted by `main`'s runtime preamble), runs it, and reads back which
+/// side of the branch ran.
+#[cfg(test)]
+mod cmp_branch_tests {
+    use super::*;
+    use crate::sim::{assemble, Cpu};
+    use std::fmt::Write as _;
+
+    /// Moves `value` into `VREG_A`, compares it against `literal` with
+    /// `cmp` (either `AsmLine::Cmp` or `AsmLine::Scmp`), then runs `branch`
+    /// (targeting the `TAKEN` label below) -- falling through stores 99
+    /// into `VREG_M` and jumps past `TAKEN`, which stores 1, so the final
+    /// value of `VREG_M` says which side ran.
+    fn run_branch(value: i32, literal: i32, cmp: fn(Arg, Arg) -> AsmLine, branch: AsmLine) -> u16 {
+        let mut src = String::new();
+        writeln!(src, "VREG_A equ 128").unwrap();
+        writeln!(src, "VREG_M equ 130").unwrap();
+        writeln!(src, "TMPW equ 132").unwrap();
+        writeln!(src, "LAST_CMP equ 134").unwrap();
+        writeln!(src, "SLAST_CMP equ 135").unwrap();
+        write!(src, "{}", AsmLine::Mov(Arg::Literal(value), Arg::VirtualRegister('A'))).unwrap();
+        write!(src, "{}", cmp(Arg::Literal(literal), Arg::VirtualRegister('A'))).unwrap();
+        write!(src, "{}", branch).unwrap();
+        write!(src, "{}", AsmLine::Mov(Arg::Literal(99), Arg::VirtualRegister('M'))).unwrap();
+        write!(src, "{}", AsmLine::Jmp(Arg::Label("END".to_string()))).unwrap();
+        write!(src, "{}", AsmLine::Label("TAKEN".to_string())).unwrap();
+        write!(src, "{}", AsmLine::Mov(Arg::Literal(1), Arg::VirtualRegister('M'))).unwrap();
+        write!(src, "{}", AsmLine::Label("END".to_string())).unwrap();
+        writeln!(src, "SET_LAST_CMP").unwrap();
+        writeln!(src, "\tBEQ @EQ").unwrap();
+        writeln!(src, "\tBCS @GT").unwrap();
+        writeln!(src, "\tLDA #1").unwrap();
+        writeln!(src, "\tSTA LAST_CMP").unwrap();
+        writeln!(src, "\tRTS").unwrap();
+        writeln!(src, "@GT\tLDA #2").unwrap();
+        writeln!(src, "\tSTA LAST_CMP").unwrap();
+        writeln!(src, "\tRTS").unwrap();
+        writeln!(src, "@EQ\tLDA #0").unwrap();
+        writeln!(src, "\tSTA LAST_CMP").unwrap();
+        writeln!(src, "\tRTS").unwrap();
+        writeln!(src, "SET_SLAST_CMP").unwrap();
+        writeln!(src, "\tBEQ @SEQ").unwrap();
+        writeln!(src, "\tBCS @SGT").unwrap();
+        writeln!(src, "\tLDA #1").unwrap();
+        writeln!(src, "\tSTA SLAST_CMP").unwrap();
+        writeln!(src, "\tRTS").unwrap();
+        writeln!(src, "@SGT\tLDA #2").unwrap();
+        writeln!(src, "\tSTA SLAST_CMP").unwrap();
+        writeln!(src, "\tRTS").unwrap();
+        writeln!(src, "@SEQ\tLDA #0").unwrap();
+        writeln!(src, "\tSTA SLAST_CMP").unwrap();
+        writeln!(src, "\tRTS").unwrap();
+
+        let program = assemble(&src);
+        let mut cpu = Cpu::new();
+        for _ in 0..10_000 {
+            if !cpu.step(&program) {
+                break;
             }
-            _ => {}
-        };
+        }
+        u16::from_le_bytes([cpu.memory[130], cpu.memory[131]])
+    }
+
+    #[test]
+    fn sixteen_bit_cmp_resolves_on_the_high_byte_at_the_0x00ff_0x0100_boundary() {
+        assert_eq!(run_branch(0x0100, 0x00FF, AsmLine::Scmp, AsmLine::Jg(Arg::Label("TAKEN".to_string()))), 1);
+        assert_eq!(run_branch(0x00FF, 0x0100, AsmLine::Scmp, AsmLine::Jl(Arg::Label("TAKEN".to_string()))), 1);
+    }
+
+    #[test]
+    fn je_branches_only_when_equal() {
+        assert_eq!(run_branch(5, 5, AsmLine::Cmp, AsmLine::Je(Arg::Label("TAKEN".to_string()))), 1);
+        assert_eq!(run_branch(5, 6, AsmLine::Cmp, AsmLine::Je(Arg::Label("TAKEN".to_string()))), 99);
+    }
+
+    #[test]
+    fn jne_branches_only_when_different() {
+        assert_eq!(run_branch(5, 6, AsmLine::Cmp, AsmLine::Jne(Arg::Label("TAKEN".to_string()))), 1);
+        assert_eq!(run_branch(5, 5, AsmLine::Cmp, AsmLine::Jne(Arg::Label("TAKEN".to_string()))), 99);
+    }
+
+    #[test]
+    fn jl_branches_only_when_less() {
+        assert_eq!(run_branch(3, 5, AsmLine::Scmp, AsmLine::Jl(Arg::Label("TAKEN".to_string()))), 1);
+        assert_eq!(run_branch(5, 3, AsmLine::Scmp, AsmLine::Jl(Arg::Label("TAKEN".to_string()))), 99);
+    }
+
+    #[test]
+    fn jg_branches_only_when_greater() {
+        assert_eq!(run_branch(5, 3, AsmLine::Scmp, AsmLine::Jg(Arg::Label("TAKEN".to_string()))), 1);
+        assert_eq!(run_branch(3, 5, AsmLine::Scmp, AsmLine::Jg(Arg::Label("TAKEN".to_string()))), 99);
+    }
+
+    #[test]
+    fn jle_branches_when_less_or_equal() {
+        assert_eq!(run_branch(5, 5, AsmLine::Scmp, AsmLine::Jle(Arg::Label("TAKEN".to_string()))), 1);
+        assert_eq!(run_branch(3, 5, AsmLine::Scmp, AsmLine::Jle(Arg::Label("TAKEN".to_string()))), 1);
+        assert_eq!(run_branch(5, 3, AsmLine::Scmp, AsmLine::Jle(Arg::Label("TAKEN".to_string()))), 99);
+    }
+
+    #[test]
+    fn jge_branches_when_greater_or_equal() {
+        assert_eq!(run_branch(5, 5, AsmLine::Scmp, AsmLine::Jge(Arg::Label("TAKEN".to_string()))), 1);
+        assert_eq!(run_branch(5, 3, AsmLine::Scmp, AsmLine::Jge(Arg::Label("TAKEN".to_string()))), 1);
+        assert_eq!(run_branch(3, 5, AsmLine::Scmp, AsmLine::Jge(Arg::Label("TAKEN".to_string()))), 99);
+    }
+
+    /// The case the unsigned `LAST_CMP` path gets backwards: 0xFFFF and
+    /// 0x0001 compare as 65535 > 1 unsigned, but as -1 < 1 signed.
+    /// `scmpb`/`jl` must follow the signed reading.
+    #[test]
+    fn jl_follows_the_signed_ordering_across_the_sign_boundary() {
+        assert_eq!(run_branch(0xFFFFu16 as i32, 1, AsmLine::Scmp, AsmLine::Jl(Arg::Label("TAKEN".to_string()))), 1);
     }
 }
 
-fn main() -> Result<(), std::io::Error> {
-    let mut transpiler = Transpiler {
-        vregs: HashSet::new(),
+const TMPW: usize = ZERO_PAGE_BASE;
+const LAST_CMP: usize = ZERO_PAGE_BASE + 2;
+const SLAST_CMP: usize = ZERO_PAGE_BASE + 3;
+
+// The allocation core itself (`LiveRange`, `RegisterAllocator`, the
+// zero-page layout constants) is `include!`d from `regalloc_core.rs`,
+// shared with the modular `transpiler/regalloc.rs` binary -- only
+// `touched_registers`, which has to pattern-match this binary's own
+// `AsmLine`, lives here.
+include!("regalloc_core.rs");
+
+impl RegisterAllocator {
+    fn build_for(program: &[AsmLine]) -> Self {
+        Self::build(program, touched_registers)
+    }
+}
+
+fn touched_registers(line: &AsmLine) -> Vec<char> {
+    let mut touched = vec![];
+    let mut note = |arg: &Arg| {
+        if let Arg::VirtualRegister(r) = arg {
+            touched.push(*r);
+        }
     };
+    match line {
+        AsmLine::Xor(a, b)
+        | AsmLine::Adc(a, b)
+        | AsmLine::Mov(a, b)
+        | AsmLine::MovZ(a, b)
+        | AsmLine::Cmp(a, b)
+        | AsmLine::Scmp(a, b) => {
+            note(a);
+            note(b);
+        }
+        AsmLine::Inc(a)
+        | AsmLine::Dec(a)
+        | AsmLine::Jmp(a)
+        | AsmLine::Je(a)
+        | AsmLine::Jne(a)
+        | AsmLine::Jl(a)
+        | AsmLine::Jg(a)
+        | AsmLine::Jle(a)
+        | AsmLine::Jge(a) => note(a),
+        AsmLine::Label(_) => {}
+    }
+    touched
+}
 
+fn main() -> Result<(), std::io::Error> {
     let file = File::open(FILENAME)?;
     let file = BufReader::new(&file);
 
     eprintln!("Parsing input file...");
-    let input: Vec<AsmLine> = file
+    let lines: Vec<String> = file
         .lines()
         .skip(1)
-        .enumerate()
-        .map(|(num, l)| {
-            print!("Line {:4}:\t", num);
-            l.expect("Parse error")
-        })
-        .map(|s| {
-            println!("{}", s);
-            let s = s.parse::<AsmLine>().expect("Parse error");
-            transpiler.check_for_virtual_registers(&s);
-            s
-        })
-        .collect();
+        .collect::<Result<_, _>>()?;
+
+    let lines = match expand_macros(&lines) {
+        Ok(lines) => lines,
+        Err(e) => {
+            eprintln!();
+            eprint!("{}", e);
+            eprintln!("Parsing failed: macro expansion error, no code generated.");
+            process::exit(1);
+        }
+    };
+
+    let mut input: Vec<AsmLine> = vec![];
+    let mut errors: Vec<AsmLineError> = vec![];
+    for (num, raw) in lines.iter().enumerate() {
+        println!("Line {:4}:\t{}", num, raw);
+        match AsmLine::parse(num, raw) {
+            Ok(line) => input.push(line),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    if !errors.is_empty() {
+        eprintln!();
+        for e in &errors {
+            eprint!("{}", e);
+        }
+        eprintln!(
+            "Parsing failed: {} error(s) found, no code generated.",
+            errors.len()
+        );
+        process::exit(1);
+    }
 
     eprintln!("Parsing complete.");
     eprintln!();
 
+    eprintln!("Allocating virtual registers...");
+    let allocator = RegisterAllocator::build_for(&input);
+    let assignment = allocator.allocate();
+
     eprintln!("Generating 6502 code...");
-    transpiler
-        .vregs
-        .iter()
-        .for_each(|reg| println!(".ZPVAR .WORD VREG_{}", reg));
-    println!("\t.ZPVAR .WORD TMPW");
-    println!("\tORG $2000");
-    input.into_iter().for_each(|l| print!("{}", l));
+    let mut generated = String::new();
+    use std::fmt::Write as _;
+    writeln!(generated, "TMPW equ {}", TMPW).unwrap();
+    writeln!(generated, "LAST_CMP equ {}", LAST_CMP).unwrap();
+    writeln!(generated, "SLAST_CMP equ {}", SLAST_CMP).unwrap();
+    let mut regs: Vec<(&char, &usize)> = assignment.iter().collect();
+    regs.sort_by_key(|(_, addr)| **addr);
+    regs.iter()
+        .for_each(|(reg, addr)| writeln!(generated, "VREG_{} equ {}", reg, addr).unwrap());
+    writeln!(generated, "\tORG $2000").unwrap();
+    input
+        .into_iter()
+        .for_each(|l| write!(generated, "{}", l).unwrap());
+
+    // LAST_CMP/SLAST_CMP are tri-state bytes set by `cmpb`/`cmpl` and
+    // `scmpb`/`scmpl` respectively: 0 = equal, 1 = less, 2 = greater. The
+    // `jcc` family just reads the matching one back.
+    write!(
+        generated,
+        r#"
+SET_LAST_CMP
+        BEQ @EQ
+        BCS @GT
+        LDA #1
+        STA LAST_CMP
+        RTS
+@GT     LDA #2
+        STA LAST_CMP
+        RTS
+@EQ     LDA #0
+        STA LAST_CMP
+        RTS
+
+SET_SLAST_CMP
+        BEQ @SEQ
+        BCS @SGT
+        LDA #1
+        STA SLAST_CMP
+        RTS
+@SGT    LDA #2
+        STA SLAST_CMP
+        RTS
+@SEQ    LDA #0
+        STA SLAST_CMP
+        RTS
+    "#
+    )
+    .unwrap();
+
+    print!("{}", generated);
     eprintln!("Code generation complete.");
     eprintln!();
 
+    if env::args().any(|arg| arg == "--run") {
+        eprintln!("Running generated program...");
+        let program = sim::assemble(&generated);
+        let mut debugger = sim::Debugger::new(program);
+
+        for label in env::args().filter_map(|arg| arg.strip_prefix("--break-label=").map(str::to_string)) {
+            debugger.break_at_label(&label);
+        }
+        for addr in env::args()
+            .filter_map(|arg| arg.strip_prefix("--break-addr=").and_then(|v| v.parse().ok()))
+        {
+            debugger.break_at(addr);
+        }
+
+        match env::args().find_map(|arg| arg.strip_prefix("--step=").and_then(|v| v.parse().ok())) {
+            Some(count) => {
+                debugger.step(count);
+            }
+            None => {
+                debugger.run(1_000_000);
+            }
+        }
+
+        eprintln!("{}", debugger.dump_registers());
+        if let Some((addr, len)) = env::args().find_map(|arg| {
+            let (addr, len) = arg.strip_prefix("--dump-mem=")?.split_once(',')?;
+            Some((addr.parse::<u16>().ok()?, len.parse::<u16>().ok()?))
+        }) {
+            eprintln!("mem[{:04X}..{:04X}] = {}", addr, addr + len, debugger.dump_memory(addr, len));
+        }
+        for (reg, addr) in &regs {
+            eprintln!(
+                "VREG_{} = {}",
+                reg,
+                u16::from_le_bytes([
+                    debugger.cpu.memory[**addr],
+                    debugger.cpu.memory[**addr + 1]
+                ])
+            );
+        }
+    }
+
     Ok(())
 }
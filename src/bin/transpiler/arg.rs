@@ -1,6 +1,6 @@
-use std::{fmt, str::FromStr};
+use std::fmt;
 
-use crate::asm_line::AsmLineError;
+use crate::asm_line::{AsmLineError, AsmLineErrorKind};
 
 #[derive(Debug)]
 pub(in crate) enum Arg {
@@ -13,76 +13,101 @@ pub(in crate) enum Arg {
 }
 
 impl Arg {
-    fn register_from_name(name: &str) -> Result<char, AsmLineError> {
+    fn register_from_name(
+        line_no: usize,
+        raw: &str,
+        span: (usize, usize),
+        name: &str,
+    ) -> Result<char, AsmLineError> {
         match name {
             "eax" | "al" => Ok('A'),
             "ecx" | "cl" => Ok('C'),
-            "ebx" | "bl"=> Ok('B'),
+            "ebx" | "bl" => Ok('B'),
             "edx" | "dl" => Ok('D'),
             "esi" => Ok('S'),
-            _ => Err(AsmLineError::MalformedRegisterName(name.to_string())),
+            _ => Err(AsmLineError::new(
+                line_no,
+                raw,
+                span,
+                AsmLineErrorKind::MalformedRegisterName,
+            )
+            .with_detail(name.to_string())),
         }
     }
-}
 
-impl fmt::Display for Arg {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Self::Label(s) => write!(f, "{}", s),
-            _ => write!(f, "Unable to generate 6502 code for argument: {:?}", self),
-        }
-    }
-}
-
-impl FromStr for Arg {
-    type Err = AsmLineError;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+    /// Parses a single argument token found at byte offset `col` within
+    /// `raw` (line `line_no`), so any failure carries a precise span.
+    pub(in crate) fn parse(line_no: usize, raw: &str, col: usize, s: &str) -> Result<Self, AsmLineError> {
         if s.is_empty() {
-            return Err(AsmLineError::EmptyArgument);
+            return Err(AsmLineError::new(
+                line_no,
+                raw,
+                (col, col),
+                AsmLineErrorKind::EmptyArgument,
+            ));
         }
 
+        let span = (col, col + s.len());
         let mut it = s.chars().peekable();
         if let Some(c) = it.peek() {
-            Ok(match c {
-                '%' => Arg::register_from_name(
-                    &it.skip(1)
-                        .filter(|c| !vec![',', '%'].contains(c))
-                        .collect::<String>(),
-                )
-                .and_then(|c| Ok(Self::VirtualRegister(c))),
-                '.' => Ok(Self::Label({
-                    it.skip(1).filter(|c| *c != ',').collect::<String>()
-                })),
+            match c {
+                '%' => {
+                    let name: String = it.skip(1).filter(|c| ![',', '%'].contains(c)).collect();
+                    let reg = Arg::register_from_name(line_no, raw, span, &name)?;
+                    Ok(Self::VirtualRegister(reg))
+                }
+                '.' => Ok(Self::Label(
+                    it.skip(1).filter(|c| *c != ',').collect::<String>(),
+                )),
                 '(' => {
                     let args: String = it.collect();
                     let args = args.trim_end_matches(')');
                     let args = args.trim_start_matches('(');
                     let args: Vec<String> = args.split(',').map(ToString::to_string).collect();
 
-                    // TODO: Simplification: edx => D, ecx => C, etc.
-                    Ok(Self::SumAddress(
-                        args[0].chars().nth(2).unwrap(),
-                        args[1].chars().nth(2).unwrap(),
-                    ))
+                    let reg_at = |i: usize| -> Result<char, AsmLineError> {
+                        args.get(i).and_then(|a| a.chars().nth(2)).ok_or_else(|| {
+                            AsmLineError::new(line_no, raw, span, AsmLineErrorKind::TruncatedSumAddress)
+                        })
+                    };
+                    Ok(Self::SumAddress(reg_at(0)?, reg_at(1)?))
                 }
-                '0'..='9' => Ok(Self::AbsoluteAddress({
-                    it.filter(|c| *c != ',')
-                        .collect::<String>()
-                        .parse::<i32>()
-                        .unwrap()
-                })),
-                '$' => Ok(Self::Literal({
-                    it.skip(1)
-                        .filter(|c| *c != ',')
-                        .collect::<String>()
-                        .parse::<i32>()
-                        .unwrap()
-                })),
-                _ => Err(AsmLineError::MalformedArgumentName(s.to_string())),
-            }?)
+                '0'..='9' => {
+                    let digits: String = it.filter(|c| *c != ',').collect();
+                    digits.parse::<i32>().map(Self::AbsoluteAddress).map_err(|_| {
+                        AsmLineError::new(line_no, raw, span, AsmLineErrorKind::BadIntegerLiteral)
+                    })
+                }
+                '$' => {
+                    let digits: String = it.skip(1).filter(|c| *c != ',').collect();
+                    digits.parse::<i32>().map(Self::Literal).map_err(|_| {
+                        AsmLineError::new(line_no, raw, span, AsmLineErrorKind::BadIntegerLiteral)
+                    })
+                }
+                _ => Err(AsmLineError::new(
+                    line_no,
+                    raw,
+                    span,
+                    AsmLineErrorKind::MalformedArgumentName,
+                )
+                .with_detail(s.to_string())),
+            }
         } else {
-            Err(AsmLineError::UnknownError)
+            Err(AsmLineError::new(
+                line_no,
+                raw,
+                span,
+                AsmLineErrorKind::EmptyArgument,
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Label(s) => write!(f, "{}", s),
+            _ => write!(f, "Unable to generate 6502 code for argument: {:?}", self),
         }
     }
 }
@@ -1,94 +1,93 @@
-#![feature(llvm_asm, const_if_match, try_trait)]
 mod arg;
 mod asm_line;
+mod peephole;
+mod regalloc;
+mod sim;
 mod source;
 
-use std::collections::HashSet;
 use std::env;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::process;
 
-use arg::Arg;
-use asm_line::AsmLine;
+use asm_line::{AsmLine, AsmLineError};
+use regalloc::RegisterAllocator;
 
 const FILENAME: &str = "output.asm";
 
-#[derive(Debug)]
-struct Transpiler {
-    pub vregs: HashSet<char>,
-}
-
-impl Transpiler {
-    fn add_vreg(&mut self, r: char) {
-        self.vregs.insert(r);
+fn main() -> Result<(), std::io::Error> {
+    if env::args().find(|arg| arg == "--nocrash").is_none() {
+        source::asm6502_source();
     }
 
-    fn insert_if_is_virtual_register(&mut self, arg: &Arg) {
-        if let Arg::VirtualRegister(r) = arg {
-            self.add_vreg(*r)
+    let file = File::open(FILENAME)?;
+    let file = BufReader::new(&file);
+
+    // Mirrors everything printed to stdout below, minus the "; Line N:"
+    // source echoes and the `ORG` line, so `--debug` has real 6502 text to
+    // hand to the sim.
+    use std::fmt::Write as _;
+    let mut generated = String::new();
+
+    eprintln!("Parsing input file...");
+    println!("\tORG $2000");
+
+    let mut lines: Vec<AsmLine> = vec![];
+    let mut errors: Vec<AsmLineError> = vec![];
+    for (num, raw) in file.lines().skip(1).enumerate() {
+        let raw = raw.expect("could not read line");
+        print!("; Line {:4}:\t", num);
+        println!("{}", raw);
+        match AsmLine::parse(num, &raw) {
+            Ok(line) => lines.push(line),
+            Err(e) => errors.push(e),
         }
     }
 
-    fn check_for_virtual_registers(&mut self, asm_line: &AsmLine) {
-        match &asm_line {
-            AsmLine::Xor(arg1, arg2)
-            | AsmLine::Adc(arg1, arg2)
-            | AsmLine::Mov(arg1, arg2)
-            | AsmLine::MovZ(arg1, arg2) => {
-                self.insert_if_is_virtual_register(arg1);
-                self.insert_if_is_virtual_register(arg2);
-            }
-            AsmLine::Inc(arg) | AsmLine::Dec(arg) | AsmLine::Jmp(arg) => {
-                self.insert_if_is_virtual_register(arg);
-            }
-            _ => {}
-        };
+    if !errors.is_empty() {
+        eprintln!();
+        for e in &errors {
+            eprint!("{}", e);
+        }
+        eprintln!(
+            "Parsing failed: {} error(s) found, no code generated.",
+            errors.len()
+        );
+        process::exit(1);
     }
-}
 
-fn main() -> Result<(), std::io::Error> {
-    if env::args().find(|arg| arg == "--nocrash").is_none() {
-        let _ = source::asm6502_source();
-    }
+    eprintln!("Allocating virtual registers...");
+    let assignment = RegisterAllocator::build_for(&lines).allocate();
+
+    let mut body = String::new();
+    lines.iter().for_each(|l| write!(body, "{}", l).unwrap());
 
-    let mut transpiler = Transpiler {
-        vregs: HashSet::new(),
+    let body = if env::args().any(|arg| arg == "--peephole") {
+        eprintln!("Running peephole optimizer...");
+        peephole::optimize(&body)
+    } else {
+        body
     };
 
-    let file = File::open(FILENAME)?;
-    let file = BufReader::new(&file);
+    print!("{}", body);
+    generated.push_str(&body);
 
-    eprintln!("Parsing input file...");
-    println!("\tORG $2000");
-    file.lines()
-        .skip(1)
-        .enumerate()
-        .map(|(num, l)| {
-            print!("; Line {:4}:\t", num);
-            l.expect("Parse error")
-        })
-        .map(|s| {
-            println!("{}", s);
-            let s = s.parse::<AsmLine>().expect("Parse error");
-            transpiler.check_for_virtual_registers(&s);
-            s
-        })
-        .for_each(|l| print!("{}\n", l));
-
-    const ZERO_PAGE_BASE: usize = 0x80;
-    const VIRTUAL_REGISTERS_BASE: usize = ZERO_PAGE_BASE + 3;
+    const ZERO_PAGE_BASE: usize = regalloc::ZERO_PAGE_BASE;
     println!("TMPW equ {}", ZERO_PAGE_BASE);
     println!("LAST_CMP equ {}", ZERO_PAGE_BASE+2);
-    transpiler
-        .vregs
-        .iter()
-        .enumerate()
-        .for_each(|(index, reg)| {
-            println!("VREG_{} equ {}", reg, VIRTUAL_REGISTERS_BASE + (index << 1));
-        });
+    println!("SLAST_CMP equ {}", ZERO_PAGE_BASE+3);
+    writeln!(generated, "TMPW equ {}", ZERO_PAGE_BASE).unwrap();
+    writeln!(generated, "LAST_CMP equ {}", ZERO_PAGE_BASE+2).unwrap();
+    writeln!(generated, "SLAST_CMP equ {}", ZERO_PAGE_BASE+3).unwrap();
+    let mut regs: Vec<(&char, &usize)> = assignment.iter().collect();
+    regs.sort_by_key(|(_, addr)| **addr);
+    regs.iter().for_each(|(reg, addr)| {
+        println!("VREG_{} equ {}", reg, addr);
+        writeln!(generated, "VREG_{} equ {}", reg, addr).unwrap();
+    });
 
     // Add runtime :)
-    println!(r#"
+    let runtime = r#"
 PAL     = $D014
 VCOUNT  = $D40B
 SYNCHRO
@@ -99,17 +98,51 @@ SYNCHRO
 SYN_0       lda #145	; PAL
 SYN_1       cmp VCOUNT
             bne SYN_1
-            rts        
+            rts
 
-LAST_CMP_EQUAL
-        BEQ @+
+SET_LAST_CMP
+        BEQ @EQ
+        BCS @GT
         LDA #1
         STA LAST_CMP
         RTS
-@       LDA #0
+@GT     LDA #2
+        STA LAST_CMP
+        RTS
+@EQ     LDA #0
         STA LAST_CMP
         RTS
-    "#);
+
+SET_SLAST_CMP
+        BEQ @SEQ
+        BCS @SGT
+        LDA #1
+        STA SLAST_CMP
+        RTS
+@SGT    LDA #2
+        STA SLAST_CMP
+        RTS
+@SEQ    LDA #0
+        STA SLAST_CMP
+        RTS
+    "#;
+    println!("{}", runtime);
+    write!(generated, "{}", runtime).unwrap();
+
+    if env::args().any(|arg| arg == "--debug") {
+        eprintln!("Starting interactive debugger (s[tep], r[un], b[reak], c[lear], d[ump regs], m[emory], v[reg], t[race])...");
+        let program = sim::assemble(&generated);
+        let mut debugger = sim::Debugger::new(program);
+        let stdin = std::io::stdin();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+                break;
+            }
+            eprintln!("{}", debugger.run_debugger_command(&line));
+        }
+    }
 
     Ok(())
 }
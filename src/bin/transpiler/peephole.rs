@@ -0,0 +1,128 @@
+//! A peephole pass over the plain-text 6502 instruction stream the
+//! `Display` impl emits, run when `--peephole` is given. Every compiled
+//! block brackets itself in `PHA`/`PLA` and adjacent blocks frequently
+//! reload a `VREG_x` byte the previous instruction just stored, so two
+//! rules chase those redundancies to a fixed point (killing one dead
+//! instruction can expose another, e.g. dropping a `PLA`/`PHA` pair can
+//! leave a `STA x` directly in front of an `LDA x`):
+//!
+//!   - `PLA` immediately followed by `PHA`: the stack ends where it
+//!     started, so both lines go. A then keeps whatever it held before
+//!     the `PLA` rather than the pulled value; every emitted block that
+//!     opens with `PHA` sets A before reading it.
+//!   - `STA x` or `LDA x` immediately followed by `LDA x`: A already
+//!     holds the value the second instruction would reload.
+//!
+//! A line carrying a label is never touched or matched across — it may be
+//! a branch target, and this pass has no control-flow graph to check.
+
+use crate::sim::parse_line;
+
+/// Runs every rule in turn and loops until none of them remove a line.
+pub(in crate) fn optimize(source: &str) -> String {
+    let mut lines: Vec<String> = source.lines().map(str::to_string).collect();
+    loop {
+        let before = lines.len();
+        lines = drop_pla_pha_roundtrip(lines);
+        lines = drop_redundant_reload(lines);
+        if lines.len() == before {
+            return lines.join("\n") + "\n";
+        }
+    }
+}
+
+fn drop_pla_pha_roundtrip(lines: Vec<String>) -> Vec<String> {
+    let mut out = Vec::with_capacity(lines.len());
+    let mut i = 0;
+    while i < lines.len() {
+        if i + 1 < lines.len() && is_bare(&lines[i], "PLA") && is_bare(&lines[i + 1], "PHA") {
+            i += 2;
+            continue;
+        }
+        out.push(lines[i].clone());
+        i += 1;
+    }
+    out
+}
+
+fn drop_redundant_reload(lines: Vec<String>) -> Vec<String> {
+    let mut out = Vec::with_capacity(lines.len());
+    let mut i = 0;
+    while i < lines.len() {
+        let reload = operand_of(&lines[i], &["STA", "LDA"])
+            .zip(operand_of(lines.get(i + 1).map(String::as_str).unwrap_or(""), &["LDA"]));
+        if let Some((a, b)) = reload {
+            if a == b {
+                out.push(lines[i].clone());
+                i += 2;
+                continue;
+            }
+        }
+        out.push(lines[i].clone());
+        i += 1;
+    }
+    out
+}
+
+/// True for a label-free line whose mnemonic is `want` and takes no operand.
+fn is_bare(raw: &str, want: &str) -> bool {
+    matches!(
+        parse_line(raw),
+        Some(line) if line.label.is_none() && line.operands.is_empty() && line.mnemonic.as_deref() == Some(want)
+    )
+}
+
+/// The single operand of a label-free line whose mnemonic is one of
+/// `mnemonics`, or `None` if the line doesn't match that shape.
+fn operand_of(raw: &str, mnemonics: &[&str]) -> Option<String> {
+    let line = parse_line(raw)?;
+    if line.label.is_some() || line.operands.len() != 1 {
+        return None;
+    }
+    let mnemonic = line.mnemonic?;
+    if mnemonics.contains(&mnemonic.as_str()) {
+        Some(line.operands[0].clone())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_pla_immediately_followed_by_pha() {
+        let out = optimize("\tLDA #1\n\tPLA\n\tPHA\n\tLDA #2\n");
+        assert_eq!(out, "\tLDA #1\n\tLDA #2\n");
+    }
+
+    #[test]
+    fn drops_reload_of_the_value_just_stored() {
+        let out = optimize("\tSTA VREG_A\n\tLDA VREG_A\n\tLDA #9\n");
+        assert_eq!(out, "\tSTA VREG_A\n\tLDA #9\n");
+    }
+
+    #[test]
+    fn keeps_reload_of_a_different_operand() {
+        let out = optimize("\tSTA VREG_A\n\tLDA VREG_B\n");
+        assert_eq!(out, "\tSTA VREG_A\n\tLDA VREG_B\n");
+    }
+
+    #[test]
+    fn chases_a_dropped_roundtrip_to_a_fixed_point() {
+        // Dropping the `PLA`/`PHA` no-op pair leaves `STA VREG_A`
+        // immediately followed by `LDA VREG_A`, a reload that only
+        // becomes redundant once the roundtrip is gone -- so the reload
+        // rule has to run after the roundtrip rule, as each pass of
+        // `optimize` runs them.
+        let out = optimize("\tSTA VREG_A\n\tPLA\n\tPHA\n\tLDA VREG_A\n");
+        assert_eq!(out, "\tSTA VREG_A\n");
+    }
+
+    #[test]
+    fn never_drops_a_line_carrying_a_branch_target_label() {
+        let src = "\tSTA VREG_A\nLOOP\tLDA VREG_A\n\tJMP LOOP\n";
+        assert_eq!(optimize(src), src);
+    }
+}
@@ -0,0 +1,73 @@
+//! Liveness-based zero-page allocation for virtual registers. A linear scan
+//! over the instruction stream's per-register live ranges lets
+//! simultaneously-live registers share the zero page without colliding,
+//! and lets dead ones free their slot for reuse, instead of handing every
+//! register its own fixed `VREG_*` slot forever. Once the pool is
+//! exhausted, the classic linear-scan move applies: evict whichever active
+//! register has the furthest-away next use (and spill *that one*) rather
+//! than always spilling the register that just became live.
+//!
+//! The allocation core itself (`LiveRange`, `RegisterAllocator`, the
+//! zero-page layout constants) is `include!`d from `regalloc_core.rs`,
+//! shared with the monolithic `transpiler.rs` binary -- only
+//! `touched_registers`, which has to pattern-match this binary's own
+//! `AsmLine`, lives here.
+
+use std::collections::HashMap;
+
+use crate::arg::Arg;
+use crate::asm_line::AsmLine;
+
+include!("../regalloc_core.rs");
+
+impl RegisterAllocator {
+    pub(in crate) fn build_for(program: &[AsmLine]) -> Self {
+        Self::build(program, touched_registers)
+    }
+}
+
+fn touched_registers(line: &AsmLine) -> Vec<char> {
+    let mut touched = vec![];
+    let mut note = |arg: &Arg| {
+        if let Arg::VirtualRegister(r) = arg {
+            touched.push(*r);
+        }
+    };
+    match line {
+        AsmLine::Xor(a, b)
+        | AsmLine::Adc(a, b)
+        | AsmLine::Mov(a, b)
+        | AsmLine::MovZ(a, b)
+        | AsmLine::Cmp(a, b)
+        | AsmLine::Scmp(a, b)
+        | AsmLine::CMovE(a, b)
+        | AsmLine::CMovNe(a, b)
+        | AsmLine::CMovBl(a, b)
+        | AsmLine::CMovAl(a, b)
+        | AsmLine::CMovBel(a, b)
+        | AsmLine::CMovAel(a, b)
+        | AsmLine::CMovL(a, b)
+        | AsmLine::CMovG(a, b)
+        | AsmLine::CMovLe(a, b)
+        | AsmLine::CMovGe(a, b) => {
+            note(a);
+            note(b);
+        }
+        AsmLine::Inc(a)
+        | AsmLine::Dec(a)
+        | AsmLine::Jmp(a)
+        | AsmLine::Je(a)
+        | AsmLine::Jne(a)
+        | AsmLine::Jb(a)
+        | AsmLine::Ja(a)
+        | AsmLine::Jbe(a)
+        | AsmLine::Jae(a)
+        | AsmLine::Jl(a)
+        | AsmLine::Jg(a)
+        | AsmLine::Jle(a)
+        | AsmLine::Jge(a)
+        | AsmLine::Push(a) => note(a),
+        AsmLine::Label(_) => {}
+    }
+    touched
+}